@@ -0,0 +1,69 @@
+//! Pure arithmetic shared by both `MorphoStore` backends' running-total
+//! accumulators (`markets.total_borrow_assets`/`total_borrow_shares`), kept
+//! separate from any SQL so it's cheap to unit test.
+
+/// Applies a signed delta to a running total, in checked i128 arithmetic.
+/// Errors instead of wrapping if the add overflows or the result would be
+/// negative, so a bug upstream (double-counted events, an out-of-order
+/// reorg replay) surfaces as a rejected write rather than a fabricated
+/// astronomical total.
+pub fn apply_delta(current: u128, delta: i128) -> eyre::Result<u128> {
+    let current = i128::try_from(current)
+        .map_err(|_| eyre::eyre!("running total {current} does not fit in i128"))?;
+    let updated = current
+        .checked_add(delta)
+        .ok_or_else(|| eyre::eyre!("running total overflowed applying delta {delta}"))?;
+
+    u128::try_from(updated)
+        .map_err(|_| eyre::eyre!("running total went negative applying delta {delta}"))
+}
+
+/// Parses a wei amount (always non-negative on the wire) into a signed delta
+/// for `apply_delta`. Errors instead of defaulting to zero on a parse failure
+/// or silently wrapping via `as` on a value too large for i128, consistent
+/// with `apply_delta`'s own policy of rejecting instead of silently
+/// corrupting a running total.
+pub fn parse_amount(amount: &str) -> eyre::Result<i128> {
+    let amount: u128 = amount.parse()?;
+    i128::try_from(amount).map_err(|_| eyre::eyre!("amount {amount} does not fit in i128"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_a_positive_delta() {
+        assert_eq!(apply_delta(100, 50).unwrap(), 150);
+    }
+
+    #[test]
+    fn subtracts_a_negative_delta() {
+        assert_eq!(apply_delta(100, -50).unwrap(), 50);
+    }
+
+    #[test]
+    fn rejects_going_negative_instead_of_wrapping() {
+        assert!(apply_delta(100, -150).is_err());
+    }
+
+    #[test]
+    fn rejects_overflow_instead_of_wrapping() {
+        assert!(apply_delta(u128::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn parses_a_valid_amount() {
+        assert_eq!(parse_amount("100").unwrap(), 100);
+    }
+
+    #[test]
+    fn rejects_unparseable_amounts_instead_of_defaulting_to_zero() {
+        assert!(parse_amount("not a number").is_err());
+    }
+
+    #[test]
+    fn rejects_amounts_too_large_for_i128_instead_of_wrapping() {
+        assert!(parse_amount(&u128::MAX.to_string()).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Morpho event decoding and the writes each event maps to. Shared between the
+//! live `morpho_monitor` path and the historical `backfill` path, so both stay in
+//! sync with what each event means for the store.
+
+use alloy_primitives::{address, Address};
+use alloy_sol_types::{sol, SolEventInterface};
+use reth_execution_types::Chain;
+use reth_node_api::BlockBody;
+use reth_primitives::{Log, Receipt, SealedBlockWithSenders, TransactionSigned};
+
+use crate::store::{MorphoStore, NewMarket};
+
+sol!(Morpho, "morpho_abi.json");
+pub use Morpho::MorphoEvents;
+
+pub const MORPHO_ADDRESS: Address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
+
+/// Decodes every Morpho log out of a live chain segment, alongside the block and
+/// transaction it came from.
+pub fn decode_chain_events(
+    chain: &Chain,
+) -> impl Iterator<
+    Item = (
+        &SealedBlockWithSenders,
+        &TransactionSigned,
+        (&Log, usize),
+        MorphoEvents,
+    ),
+> {
+    chain
+        .blocks_and_receipts()
+        .flat_map(|(block, receipts)| {
+            block
+                .body
+                .transactions()
+                .into_iter()
+                .zip(receipts.iter().flatten())
+                .map(move |(tx, receipt)| (block, tx, receipt))
+        })
+        .flat_map(|(block, tx, receipt)| {
+            receipt
+                .logs
+                .iter()
+                .enumerate()
+                .filter(|(_, log)| log.address == MORPHO_ADDRESS)
+                .map(move |(idx, log)| (block, tx, (log, idx)))
+        })
+        .filter_map(|(block, tx, (log, idx))| {
+            MorphoEvents::decode_raw_log(log.topics(), &log.data.data, true)
+                .ok()
+                .map(|event| (block, tx, (log, idx), event))
+        })
+}
+
+/// Decodes every Morpho log out of a single historical block's receipts, for the
+/// backfill path (which doesn't need the transaction, only the event and its
+/// per-receipt log index).
+pub fn decode_receipt_events(
+    receipts: &[Receipt],
+) -> impl Iterator<Item = (usize, MorphoEvents)> + '_ {
+    receipts
+        .iter()
+        .flat_map(|receipt| {
+            receipt
+                .logs
+                .iter()
+                .enumerate()
+                .filter(|(_, log)| log.address == MORPHO_ADDRESS)
+        })
+        .filter_map(|(idx, log)| {
+            MorphoEvents::decode_raw_log(log.topics(), &log.data.data, true)
+                .ok()
+                .map(|event| (idx, event))
+        })
+}
+
+/// Applies a single decoded Morpho event to the store. `log_index` and
+/// `block_number`/`timestamp` come from wherever the event was decoded (a live
+/// chain segment or a backfilled historical block).
+pub async fn apply_morpho_event(
+    store: &dyn MorphoStore,
+    event: MorphoEvents,
+    log_index: i64,
+    block_number: i64,
+    timestamp: i64,
+) -> eyre::Result<()> {
+    match event {
+        MorphoEvents::CreateMarket(e) => {
+            store
+                .create_market(NewMarket {
+                    id: e.id.to_string(),
+                    loan_token: e.marketParams.loanToken.to_string(),
+                    collateral_token: e.marketParams.collateralToken.to_string(),
+                    oracle: e.marketParams.oracle.to_string(),
+                    irm: e.marketParams.irm.to_string(),
+                    lltv: e.marketParams.lltv.to_string(),
+                    timestamp,
+                })
+                .await?;
+        }
+        MorphoEvents::SupplyCollateral(e) => {
+            store
+                .supply_collateral(
+                    &e.id.to_string(),
+                    &e.onBehalf.to_string(),
+                    &e.assets.to_string(),
+                    timestamp,
+                )
+                .await?;
+        }
+        MorphoEvents::Borrow(e) => {
+            store
+                .borrow(
+                    &e.id.to_string(),
+                    &e.onBehalf.to_string(),
+                    &e.shares.to_string(),
+                    &e.assets.to_string(),
+                    log_index,
+                    block_number,
+                    timestamp,
+                )
+                .await?;
+        }
+        MorphoEvents::Repay(e) => {
+            store
+                .repay(
+                    &e.id.to_string(),
+                    &e.onBehalf.to_string(),
+                    &e.shares.to_string(),
+                    &e.assets.to_string(),
+                    log_index,
+                    block_number,
+                    timestamp,
+                )
+                .await?;
+        }
+        MorphoEvents::WithdrawCollateral(e) => {
+            store
+                .withdraw_collateral(
+                    &e.id.to_string(),
+                    &e.onBehalf.to_string(),
+                    &e.assets.to_string(),
+                    timestamp,
+                )
+                .await?;
+        }
+        MorphoEvents::AccrueInterest(e) => {
+            store
+                .accrue_interest(
+                    &e.id.to_string(),
+                    &e.interest.to_string(),
+                    log_index,
+                    block_number,
+                    timestamp,
+                )
+                .await?;
+        }
+        MorphoEvents::Liquidate(e) => {
+            store
+                .liquidate(
+                    &e.id.to_string(),
+                    &e.borrower.to_string(),
+                    &e.repaidShares.to_string(),
+                    &e.repaidAssets.to_string(),
+                    &e.badDebtShares.to_string(),
+                    &e.badDebtAssets.to_string(),
+                    &e.seizedAssets.to_string(),
+                    log_index,
+                    block_number,
+                    timestamp,
+                )
+                .await?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
@@ -1,271 +1,102 @@
-use alloy_primitives::{address, Address};
-use alloy_sol_types::{sol, SolEventInterface};
-use futures::{Future, FutureExt, TryStreamExt};
+use std::sync::Arc;
+
+use clap::Parser;
+use futures::{Future, TryStreamExt};
+use reth::chainspec::EthereumChainSpecParser;
 use reth_execution_types::Chain;
 use reth_exex::{ExExContext, ExExEvent};
-use reth_node_api::BlockBody;
 use reth_node_api::FullNodeComponents;
 use reth_node_ethereum::EthereumNode;
-use reth_primitives::{Log, SealedBlockWithSenders, TransactionSigned};
 use reth_tracing::tracing::info;
-use rusqlite::Connection;
 
-sol!(Morpho, "morpho_abi.json");
-use Morpho::MorphoEvents;
+mod accrual;
+mod backfill;
+mod events;
+mod migrations;
+mod oracle;
+mod store;
+
+use events::{apply_morpho_event, decode_chain_events};
+use oracle::ORACLE_PRICE_SCALE;
+use store::{MorphoStore, PositionHealth, PostgresStore, SqliteStore, StorageBackend};
+
+/// Extra CLI args accepted on top of reth's own node flags.
+#[derive(Debug, Clone, clap::Args)]
+struct MorphoExExArgs {
+    /// Which storage backend to write market/position data to.
+    #[arg(long = "morpho.backend", value_enum, default_value = "sqlite")]
+    backend: StorageBackend,
+
+    /// SQLite file path (used when `--morpho.backend sqlite`).
+    #[arg(long = "morpho.sqlite-path", default_value = "morpho.db")]
+    sqlite_path: String,
 
-const MORPHO_ADDRESS: Address = address!("BBBBBbbBBb9cC5e90e3b3Af64bdAF62C37EEFFCb");
+    /// Postgres connection string (used when `--morpho.backend postgres`).
+    #[arg(long = "morpho.postgres-url")]
+    postgres_url: Option<String>,
+
+    /// Block to start a historical backfill from. When set, the ExEx walks
+    /// `[start, tip]` before switching to live notifications, so markets created
+    /// before this ExEx was installed aren't invisible until they emit a new event.
+    #[arg(long = "morpho.backfill-start-block")]
+    backfill_start_block: Option<u64>,
+
+    /// Number of blocks fetched concurrently during backfill.
+    #[arg(long = "morpho.backfill-concurrency", default_value_t = 10)]
+    backfill_concurrency: usize,
+}
+
+async fn open_store(args: &MorphoExExArgs) -> eyre::Result<Arc<dyn MorphoStore>> {
+    match args.backend {
+        StorageBackend::Sqlite => {
+            // `SqliteStore::open` runs migrations synchronously, including migration
+            // 2's multi-table rewrite, so it's offloaded to a blocking thread rather
+            // than run directly in this async fn and risk stalling the runtime.
+            let path = args.sqlite_path.clone();
+            let store = tokio::task::spawn_blocking(move || SqliteStore::open(&path)).await??;
+            Ok(Arc::new(store))
+        }
+        StorageBackend::Postgres => {
+            let url = args.postgres_url.as_deref().ok_or_else(|| {
+                eyre::eyre!("--morpho.postgres-url is required for the postgres backend")
+            })?;
+            Ok(Arc::new(PostgresStore::connect(url).await?))
+        }
+    }
+}
 
 async fn init<Node: FullNodeComponents>(
     ctx: ExExContext<Node>,
-    mut connection: Connection,
+    store: Arc<dyn MorphoStore>,
+    backfill_config: Option<backfill::BackfillConfig>,
 ) -> eyre::Result<impl Future<Output = eyre::Result<()>>> {
-    create_tables(&mut connection)?;
-    Ok(morpho_monitor(ctx, connection))
-}
-
-fn create_tables(connection: &mut Connection) -> rusqlite::Result<()> {
-    connection.execute(
-        r#"
-        CREATE TABLE IF NOT EXISTS markets (
-            id TEXT PRIMARY KEY,
-            loan_token TEXT NOT NULL,
-            collateral_token TEXT NOT NULL,
-            oracle TEXT NOT NULL,
-            irm TEXT NOT NULL,
-            lltv TEXT NOT NULL,
-            total_borrow_assets TEXT NOT NULL,
-            total_borrow_shares TEXT NOT NULL,
-            last_update INTEGER NOT NULL
-        );"#,
-        (),
-    )?;
-
-    connection.execute(
-        r#"
-        CREATE TABLE IF NOT EXISTS positions (
-            market_id TEXT NOT NULL,
-            borrower TEXT NOT NULL,
-            borrow_shares TEXT NOT NULL,
-            collateral TEXT NOT NULL,
-            last_updated INTEGER NOT NULL,
-            PRIMARY KEY (market_id, borrower)
-        );"#,
-        (),
-    )?;
-
-    connection.execute(
-        r#"
-        CREATE TABLE IF NOT EXISTS oracle_prices (
-            oracle_address TEXT NOT NULL,
-            price TEXT NOT NULL,
-            block_number INTEGER NOT NULL,
-            timestamp INTEGER NOT NULL,
-            PRIMARY KEY (oracle_address, block_number)
-        );"#,
-        (),
-    )?;
-
-    connection.execute(
-        r#"
-        CREATE TABLE IF NOT EXISTS market_states (
-            market_id TEXT NOT NULL,
-            total_borrow_assets TEXT NOT NULL,
-            total_borrow_shares TEXT NOT NULL,
-            log_index INTEGER NOT NULL,
-            block_number INTEGER NOT NULL,
-            timestamp INTEGER NOT NULL,
-            PRIMARY KEY (market_id, block_number, log_index)
-        );"#,
-        (),
-    )?;
+    if let Some(config) = backfill_config {
+        backfill::run(&ctx.components, store.as_ref(), config).await?;
+    }
 
-    Ok(())
+    Ok(morpho_monitor(ctx, store))
 }
 
 async fn morpho_monitor<Node: FullNodeComponents>(
     mut ctx: ExExContext<Node>,
-    connection: Connection,
+    store: Arc<dyn MorphoStore>,
 ) -> eyre::Result<()> {
     while let Some(notification) = ctx.notifications.try_next().await? {
-        // Handle chain reorgs/reverts
-        if let Some(reverted_chain) = notification.reverted_chain() {
-            info!(chain_range = ?reverted_chain.range(), "Reverting chain");
-
-            let start_block = *reverted_chain.range().start();
-
-            connection.execute(
-                "DELETE FROM positions WHERE last_updated >= ?",
-                [start_block],
-            )?;
-
-            connection.execute(
-                "DELETE FROM market_states WHERE block_number >= ?",
-                [start_block],
-            )?;
-
-            connection.execute(
-                "DELETE FROM oracle_prices WHERE block_number >= ?",
-                [start_block],
-            )?;
-        }
+        // Apply the whole notification (reorg deletes, event writes, the oracle
+        // refresh and the health check) inside a single transaction, so a crash or
+        // error partway through leaves the DB exactly as it was before the
+        // notification arrived, and the ExEx can safely re-process it.
+        store.begin().await?;
 
-        if let Some(committed_chain) = notification.committed_chain() {
-            info!(chain_range = ?committed_chain.range(), "Processing new chain");
-
-            for (block, _tx, (log, log_idx), event) in decode_chain_events(&committed_chain) {
-                match event {
-                    MorphoEvents::CreateMarket(e) => {
-                        connection.execute(
-                            "INSERT INTO markets VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
-                            (
-                                e.id.to_string(),
-                                e.marketParams.loanToken.to_string(),
-                                e.marketParams.collateralToken.to_string(),
-                                e.marketParams.oracle.to_string(),
-                                e.marketParams.irm.to_string(),
-                                e.marketParams.lltv.to_string(),
-                                "0",
-                                "0",
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    MorphoEvents::SupplyCollateral(e) => {
-                        connection.execute(
-                            r#"
-                            INSERT INTO positions (market_id, borrower, borrow_shares, collateral, last_updated)
-                            VALUES (?, ?, ?, ?, ?)
-                            ON CONFLICT(market_id, borrower) DO UPDATE SET
-                            collateral = CAST(collateral AS INTEGER) + ?,
-                            last_updated = ?
-                            "#,
-                            (
-                                e.id.to_string(),
-                                e.onBehalf.to_string(),
-                                "0",
-                                e.assets.to_string(),
-                                block.timestamp,
-                                e.assets.to_string(),
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    MorphoEvents::Borrow(e) => {
-                        connection.execute(
-                            r#"
-                            INSERT INTO positions (market_id, borrower, borrow_shares, collateral, last_updated)
-                            VALUES (?, ?, ?, ?, ?)
-                            ON CONFLICT(market_id, borrower) DO UPDATE SET
-                            borrow_shares = CAST(borrow_shares AS INTEGER) + ?,
-                            last_updated = ?
-                            "#,
-                            (
-                                e.id.to_string(),
-                                e.onBehalf.to_string(),
-                                e.shares.to_string(),
-                                "0",
-                                block.timestamp,
-                                e.shares.to_string(),
-                                block.timestamp,
-                            ),
-                        )?;
-
-                        connection.execute(
-                            "INSERT INTO market_states VALUES (?, ?, ?, ?, ?, ?)",
-                            (
-                                e.id.to_string(),
-                                e.assets.to_string(),
-                                e.shares.to_string(),
-                                log_idx,
-                                block.number,
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    MorphoEvents::Repay(e) => {
-                        connection.execute(
-                            r#"
-                            INSERT INTO positions (market_id, borrower, borrow_shares, collateral, last_updated)
-                            VALUES (?, ?, ?, ?, ?)
-                            ON CONFLICT(market_id, borrower) DO UPDATE SET
-                            borrow_shares = CAST(borrow_shares AS INTEGER) - ?,
-                            last_updated = ?
-                            "#,
-                            (
-                                e.id.to_string(),
-                                e.onBehalf.to_string(),
-                                "0",
-                                "0",
-                                block.timestamp,
-                                e.shares.to_string(),
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    MorphoEvents::WithdrawCollateral(e) => {
-                        connection.execute(
-                            r#"
-                            INSERT INTO positions (market_id, borrower, borrow_shares, collateral, last_updated)
-                            VALUES (?, ?, ?, ?, ?)
-                            ON CONFLICT(market_id, borrower) DO UPDATE SET
-                            collateral = CAST(collateral AS INTEGER) - ?,
-                            last_updated = ?
-                            "#,
-                            (
-                                e.id.to_string(),
-                                e.onBehalf.to_string(),
-                                "0",
-                                "0",
-                                block.timestamp,
-                                e.assets.to_string(),
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    MorphoEvents::AccrueInterest(e) => {
-                        connection.execute(
-                            "INSERT INTO market_states VALUES (?, ?, ?, ?, ?, ?)",
-                            (
-                                e.id.to_string(),
-                                e.interest.to_string(),
-                                "0",
-                                log_idx,
-                                block.number,
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    MorphoEvents::Liquidate(e) => {
-                        connection.execute(
-                            r#"
-                            INSERT INTO positions (market_id, borrower, borrow_shares, collateral, last_updated)
-                            VALUES (?, ?, ?, ?, ?)
-                            ON CONFLICT(market_id, borrower) DO UPDATE SET
-                            borrow_shares = CAST(borrow_shares AS INTEGER) - ? - ?,
-                            collateral = CAST(collateral AS INTEGER) - ?,
-                            last_updated = ?
-                            "#,
-                            (
-                                e.id.to_string(),
-                                e.borrower.to_string(),
-                                "0",
-                                "0",
-                                block.timestamp,
-                                e.repaidShares.to_string(),
-                                e.badDebtShares.to_string(),
-                                e.seizedAssets.to_string(),
-                                block.timestamp,
-                            ),
-                        )?;
-                    }
-                    _ => {
-                        continue;
-                    }
-                }
-            }
+        let outcome = apply_notification(&ctx, store.as_ref(), &notification).await;
 
-            check_positions(&committed_chain, &connection).await?;
+        if let Err(err) = outcome {
+            store.rollback().await?;
+            return Err(err);
+        }
+        store.commit().await?;
 
+        if let Some(committed_chain) = notification.committed_chain() {
             ctx.events
                 .send(ExExEvent::FinishedHeight(committed_chain.tip().num_hash()))?;
         }
@@ -274,45 +105,45 @@ async fn morpho_monitor<Node: FullNodeComponents>(
     Ok(())
 }
 
-fn decode_chain_events(
-    chain: &Chain,
-) -> impl Iterator<
-    Item = (
-        &SealedBlockWithSenders,
-        &TransactionSigned,
-        (&Log, usize),
-        MorphoEvents,
-    ),
-> {
-    chain
-        .blocks_and_receipts()
-        .flat_map(|(block, receipts)| {
-            block
-                .body
-                .transactions()
-                .into_iter()
-                .zip(receipts.iter().flatten())
-                .map(move |(tx, receipt)| (block, tx, receipt))
-        })
-        .flat_map(|(block, tx, receipt)| {
-            receipt
-                .logs
-                .iter()
-                .enumerate()
-                .filter(|(_, log)| log.address == MORPHO_ADDRESS)
-                .map(move |(idx, log)| (block, tx, (log, idx)))
-        })
-        .filter_map(|(block, tx, (log, idx))| {
-            MorphoEvents::decode_raw_log(log.topics(), &log.data.data, true)
-                .ok()
-                .map(|event| (block, tx, (log, idx), event))
-        })
+async fn apply_notification<Node: FullNodeComponents>(
+    ctx: &ExExContext<Node>,
+    store: &dyn MorphoStore,
+    notification: &reth_exex::ExExNotification,
+) -> eyre::Result<()> {
+    // Handle chain reorgs/reverts
+    if let Some(reverted_chain) = notification.reverted_chain() {
+        info!(chain_range = ?reverted_chain.range(), "Reverting chain");
+
+        let start_block = *reverted_chain.range().start();
+        store.revert_from(start_block as i64).await?;
+    }
+
+    if let Some(committed_chain) = notification.committed_chain() {
+        info!(chain_range = ?committed_chain.range(), "Processing new chain");
+
+        for (block, _tx, (_log, log_idx), event) in decode_chain_events(&committed_chain) {
+            apply_morpho_event(
+                store,
+                event,
+                log_idx as i64,
+                block.number as i64,
+                block.timestamp as i64,
+            )
+            .await?;
+        }
+
+        let tip = committed_chain.tip();
+        oracle::update_oracle_prices(&ctx.components, store, tip.number, tip.timestamp).await?;
+
+        check_positions(&committed_chain, store).await?;
+    }
+
+    Ok(())
 }
 
 const WARNING_THRESHOLD: f64 = 0.95;
 const HIGH_RISK_THRESHOLD: f64 = 0.98;
 const WAD: u128 = 1_000_000_000_000_000_000;
-const ORACLE_PRICE_SCALE: u128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
 
 fn calculate_position_metrics(
     borrow_shares: String,
@@ -321,83 +152,52 @@ fn calculate_position_metrics(
     collateral: String,
     price: String,
     lltv: String,
-) -> rusqlite::Result<(bool, f64)> {
-    let borrow_shares = borrow_shares
-        .parse::<u128>()
-        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-    let total_borrow_assets = total_borrow_assets
-        .parse::<u128>()
-        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-    let total_borrow_shares = total_borrow_shares
-        .parse::<u128>()
-        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-    let collateral = collateral
-        .parse::<u128>()
-        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-    let price = price
-        .parse::<u128>()
-        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-    let lltv = lltv
-        .parse::<u128>()
-        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
-
-    let borrowed = (borrow_shares as f64 * total_borrow_assets as f64) / total_borrow_shares as f64;
-    let collateral_value = (collateral as f64 * price as f64) / ORACLE_PRICE_SCALE as f64;
-    let max_borrow = (collateral_value * lltv as f64) / WAD as f64;
-
-    Ok((max_borrow >= borrowed, borrowed / collateral_value))
-}
-
-async fn check_positions(chain: &Chain, connection: &Connection) -> rusqlite::Result<()> {
-    let mut stmt = connection.prepare(
-        r#"
-        SELECT 
-            p.market_id,
-            p.borrower,
-            p.borrow_shares,
-            p.collateral,
-            m.total_borrow_assets,
-            m.total_borrow_shares,
-            m.lltv,
-            m.oracle,
-            op.price
-        FROM positions p
-        INNER JOIN markets m ON p.market_id = m.id
-        INNER JOIN oracle_prices op ON m.oracle = op.oracle_address
-        WHERE p.borrow_shares > 0 
-        AND p.collateral > 0
-        AND op.block_number = ?
-        "#,
-    )?;
+) -> eyre::Result<(bool, f64)> {
+    let borrow_shares = borrow_shares.parse::<u128>()?;
+    let total_borrow_assets = total_borrow_assets.parse::<u128>()?;
+    let total_borrow_shares = total_borrow_shares.parse::<u128>()?;
+    let collateral = collateral.parse::<u128>()?;
+    let price = price.parse::<u128>()?;
+    let lltv = lltv.parse::<u128>()?;
 
-    for block in chain.blocks() {
-        let positions = stmt.query_map([block.0], |row| {
-            Ok((
-                row.get::<_, String>(0)?,
-                row.get::<_, String>(1)?,
-                row.get::<_, String>(2)?,
-                row.get::<_, String>(3)?,
-                row.get::<_, String>(4)?,
-                row.get::<_, String>(5)?,
-                row.get::<_, String>(6)?,
-                row.get::<_, String>(7)?,
-                row.get::<_, String>(8)?,
-            ))
+    // u128 throughout: these values easily exceed f64's 53 bits of exact mantissa,
+    // and a multiply before the divide would silently round off real wei.
+    let borrowed = borrow_shares
+        .checked_mul(total_borrow_assets)
+        .ok_or_else(|| eyre::eyre!("borrowed amount overflowed u128"))?
+        .checked_div(total_borrow_shares)
+        .ok_or_else(|| {
+            eyre::eyre!("total_borrow_shares is zero for a market with an open position")
         })?;
+    let collateral_value = collateral
+        .checked_mul(price)
+        .ok_or_else(|| eyre::eyre!("collateral value overflowed u128"))?
+        / ORACLE_PRICE_SCALE;
+    let max_borrow = collateral_value
+        .checked_mul(lltv)
+        .ok_or_else(|| eyre::eyre!("max borrow overflowed u128"))?
+        / WAD;
 
-        for position in positions {
-            let (
-                market_id,
-                borrower,
-                borrow_shares,
-                collateral,
-                total_borrow_assets,
-                total_borrow_shares,
-                lltv,
-                _oracle,
-                price,
-            ) = position?;
+    let ltv_ratio = borrowed as f64 / collateral_value as f64;
+
+    Ok((max_borrow >= borrowed, ltv_ratio))
+}
 
+async fn check_positions(chain: &Chain, store: &dyn MorphoStore) -> eyre::Result<()> {
+    for block in chain.blocks() {
+        let positions = store.open_positions_at(*block.0 as i64).await?;
+
+        for PositionHealth {
+            market_id,
+            borrower,
+            borrow_shares,
+            collateral,
+            total_borrow_assets,
+            total_borrow_shares,
+            lltv,
+            price,
+        } in positions
+        {
             if let Ok((is_healthy, ltv_ratio)) = calculate_position_metrics(
                 borrow_shares,
                 total_borrow_assets,
@@ -436,21 +236,75 @@ async fn check_positions(chain: &Chain, connection: &Connection) -> rusqlite::Re
 }
 
 fn main() -> eyre::Result<()> {
-    reth::cli::Cli::parse_args().run(|builder, _| async move {
-        let handle = builder
-            .node(EthereumNode::default())
-            .install_exex("morpho-monitor", move |ctx| {
-                tokio::task::spawn_blocking(move || {
-                    tokio::runtime::Handle::current().block_on(async move {
-                        let connection = Connection::open("morpho.db")?;
-                        init(ctx, connection).await
-                    })
+    reth::cli::Cli::<EthereumChainSpecParser, MorphoExExArgs>::parse().run(
+        |builder, args| async move {
+            let store = open_store(&args).await?;
+            let backfill_config =
+                args.backfill_start_block
+                    .map(|start_block| backfill::BackfillConfig {
+                        start_block,
+                        concurrency: args.backfill_concurrency,
+                    });
+
+            let handle = builder
+                .node(EthereumNode::default())
+                .install_exex("morpho-monitor", move |ctx| {
+                    init(ctx, store, backfill_config)
                 })
-                .map(|result| result.map_err(Into::into).and_then(|result| result))
-            })
-            .launch()
-            .await?;
+                .launch()
+                .await?;
+
+            handle.wait_for_node_exit().await
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_position_below_lltv() {
+        let (is_healthy, ltv_ratio) = calculate_position_metrics(
+            "50".to_string(),
+            "100".to_string(),
+            "100".to_string(),
+            "1".to_string(),
+            ORACLE_PRICE_SCALE.to_string(),
+            (WAD / 2).to_string(),
+        )
+        .unwrap();
 
-        handle.wait_for_node_exit().await
-    })
+        assert!(is_healthy);
+        assert!((ltv_ratio - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn liquidatable_position_above_lltv() {
+        let (is_healthy, _) = calculate_position_metrics(
+            "90".to_string(),
+            "100".to_string(),
+            "100".to_string(),
+            "1".to_string(),
+            ORACLE_PRICE_SCALE.to_string(),
+            (WAD / 2).to_string(),
+        )
+        .unwrap();
+
+        assert!(!is_healthy);
+    }
+
+    #[test]
+    fn errors_instead_of_panicking_on_zero_total_borrow_shares() {
+        let result = calculate_position_metrics(
+            "50".to_string(),
+            "0".to_string(),
+            "0".to_string(),
+            "1".to_string(),
+            ORACLE_PRICE_SCALE.to_string(),
+            (WAD / 2).to_string(),
+        );
+
+        assert!(result.is_err());
+    }
 }
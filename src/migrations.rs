@@ -0,0 +1,125 @@
+//! Schema-versioned migrations.
+//!
+//! `CREATE TABLE IF NOT EXISTS` can't evolve a column once it exists, so schema
+//! changes (e.g. adding interest-rate fields to `markets`) would otherwise require
+//! manual DB surgery. Instead, each backend keeps an ordered list of `Migration`s
+//! and a `schema_version` table tracking how far it's gotten; at startup we apply
+//! only the migrations newer than the recorded version, inside a transaction.
+
+use reth_tracing::tracing::info;
+use rusqlite::Connection;
+use tokio_postgres::Client;
+
+/// One forward step in a backend's schema history. `version` must be unique and
+/// increasing; migrations run in ascending `version` order.
+pub struct Migration {
+    pub version: i64,
+    pub up_sql: &'static str,
+}
+
+pub fn apply_sqlite(connection: &mut Connection, migrations: &[Migration]) -> rusqlite::Result<()> {
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        (),
+    )?;
+
+    let current: i64 = connection.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        (),
+        |row| row.get(0),
+    )?;
+
+    let tx = connection.transaction()?;
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        info!(version = migration.version, "applying sqlite migration");
+        tx.execute_batch(migration.up_sql)?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES (?)",
+            [migration.version],
+        )?;
+    }
+    tx.commit()
+}
+
+pub async fn apply_postgres(client: &mut Client, migrations: &[Migration]) -> eyre::Result<()> {
+    client
+        .batch_execute("CREATE TABLE IF NOT EXISTS schema_version (version BIGINT NOT NULL)")
+        .await?;
+
+    let current: i64 = client
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])
+        .await?
+        .get(0);
+
+    let tx = client.transaction().await?;
+    for migration in migrations.iter().filter(|m| m.version > current) {
+        info!(version = migration.version, "applying postgres migration");
+        tx.batch_execute(migration.up_sql).await?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES ($1)",
+            &[&migration.version],
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            up_sql: "CREATE TABLE widgets (id INTEGER PRIMARY KEY)",
+        },
+        Migration {
+            version: 2,
+            up_sql: "ALTER TABLE widgets ADD COLUMN name TEXT",
+        },
+    ];
+
+    fn current_version(connection: &Connection) -> i64 {
+        connection
+            .query_row(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn applies_all_migrations_in_order_on_a_fresh_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        apply_sqlite(&mut connection, MIGRATIONS).unwrap();
+
+        assert_eq!(current_version(&connection), 2);
+        connection
+            .execute("INSERT INTO widgets (id, name) VALUES (1, 'a')", ())
+            .unwrap();
+    }
+
+    #[test]
+    fn only_applies_migrations_newer_than_the_recorded_version() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        apply_sqlite(&mut connection, &MIGRATIONS[..1]).unwrap();
+        assert_eq!(current_version(&connection), 1);
+
+        // Re-running with the full list should only apply version 2, not fail by
+        // re-running version 1's CREATE TABLE against a table that already exists.
+        apply_sqlite(&mut connection, MIGRATIONS).unwrap();
+        assert_eq!(current_version(&connection), 2);
+    }
+
+    #[test]
+    fn re_running_the_same_migrations_is_a_no_op() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        apply_sqlite(&mut connection, MIGRATIONS).unwrap();
+        apply_sqlite(&mut connection, MIGRATIONS).unwrap();
+
+        assert_eq!(current_version(&connection), 2);
+    }
+}
@@ -0,0 +1,87 @@
+//! Oracle price ingestion.
+//!
+//! `check_positions` needs a price for every market's oracle at the block it is
+//! evaluating, but nothing writes to `oracle_prices` unless we go fetch it. This
+//! module does that: for each oracle backing at least one open position, it reads
+//! the oracle contract's `price()` at the tip of the committed range directly from
+//! the node's state (no remote RPC needed, since the ExEx already has the state).
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::{sol, SolCall};
+use reth_node_api::FullNodeComponents;
+use reth_provider::StateProviderFactory;
+use reth_revm::database::StateProviderDatabase;
+use revm::primitives::{ExecutionResult, Output, TransactTo, TxEnv};
+use revm::Evm;
+
+use crate::store::MorphoStore;
+
+sol! {
+    interface IOracle {
+        function price() external view returns (uint256);
+    }
+}
+
+/// Scale factor Morpho oracles return `price()` in (1e36), so that
+/// `collateral * price / ORACLE_PRICE_SCALE` yields loan-token units.
+pub const ORACLE_PRICE_SCALE: u128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
+
+/// Calls `price()` on `oracle` against the state at `block_number` using the
+/// node's own state provider, mirroring a read-only `eth_call` without round
+/// tripping through RPC.
+fn call_oracle_price<Node: FullNodeComponents>(
+    components: &Node,
+    block_number: u64,
+    oracle: Address,
+) -> eyre::Result<u128> {
+    let state = components
+        .provider()
+        .state_by_block_number_or_tag(block_number.into())?;
+    let db = StateProviderDatabase::new(state);
+
+    let mut evm = Evm::builder()
+        .with_db(db)
+        .with_tx_env(TxEnv {
+            caller: Address::ZERO,
+            transact_to: TransactTo::Call(oracle),
+            data: IOracle::priceCall {}.abi_encode().into(),
+            value: U256::ZERO,
+            ..Default::default()
+        })
+        .build();
+
+    let result = evm.transact()?.result;
+    let ExecutionResult::Success {
+        output: Output::Call(bytes),
+        ..
+    } = result
+    else {
+        eyre::bail!("oracle {oracle} price() call reverted or halted at block {block_number}");
+    };
+
+    U256::from_be_slice(&bytes)
+        .checked_to::<u128>()
+        .ok_or_else(|| {
+            eyre::eyre!("oracle {oracle} price() returned a value that doesn't fit in u128 at block {block_number}")
+        })
+}
+
+/// Fetches and persists `price()` for every oracle backing an open position, at
+/// the state committed at `block_number`.
+pub async fn update_oracle_prices<Node: FullNodeComponents>(
+    components: &Node,
+    store: &dyn MorphoStore,
+    block_number: u64,
+    timestamp: u64,
+) -> eyre::Result<()> {
+    let oracles = store.oracles_with_open_positions().await?;
+
+    for oracle in oracles {
+        let price = call_oracle_price(components, block_number, oracle)?;
+        store
+            .upsert_oracle_price(oracle, price, block_number as i64, timestamp as i64)
+            .await?;
+    }
+
+    Ok(())
+}
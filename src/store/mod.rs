@@ -0,0 +1,162 @@
+//! Storage backend abstraction.
+//!
+//! Everything used to be hard-wired to a single `rusqlite::Connection`. `MorphoStore`
+//! pulls the four write paths (`markets`, `positions`, `market_states`, `oracle_prices`)
+//! and the `check_positions` read query behind a trait so the ExEx can be pointed at
+//! either a local SQLite file or a shared Postgres database.
+
+pub mod postgres;
+pub mod sqlite;
+
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use clap::ValueEnum;
+
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+/// Which `MorphoStore` implementation to open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StorageBackend {
+    Sqlite,
+    Postgres,
+}
+
+/// A `CreateMarket` event, ready to persist.
+pub struct NewMarket {
+    pub id: String,
+    pub loan_token: String,
+    pub collateral_token: String,
+    pub oracle: String,
+    pub irm: String,
+    pub lltv: String,
+    pub timestamp: i64,
+}
+
+/// A row joined out of `positions`/`markets`/`oracle_prices`, as consumed by
+/// `calculate_position_metrics`.
+pub struct PositionHealth {
+    pub market_id: String,
+    pub borrower: String,
+    pub borrow_shares: String,
+    pub collateral: String,
+    pub total_borrow_assets: String,
+    pub total_borrow_shares: String,
+    pub lltv: String,
+    pub price: String,
+}
+
+/// Abstracts the read/write paths `morpho_monitor` needs over the event log, so the
+/// ExEx doesn't care whether it's backed by SQLite or Postgres.
+///
+/// `borrow`/`repay`/`accrue_interest`/`liquidate` all read-modify-write the running
+/// `markets.total_borrow_assets`/`total_borrow_shares` totals (in u128, not f64) before
+/// snapshotting them into `market_states`, so `open_positions_at` always joins against
+/// the market's true totals instead of the `"0"` placeholders `create_market` leaves
+/// behind.
+#[async_trait]
+pub trait MorphoStore: Send + Sync {
+    async fn create_market(&self, market: NewMarket) -> eyre::Result<()>;
+
+    async fn supply_collateral(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        assets: &str,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    async fn borrow(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        shares: &str,
+        assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    async fn repay(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        shares: &str,
+        assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    async fn withdraw_collateral(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        assets: &str,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    async fn accrue_interest(
+        &self,
+        market_id: &str,
+        interest: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    async fn liquidate(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        repaid_shares: &str,
+        repaid_assets: &str,
+        bad_debt_shares: &str,
+        bad_debt_assets: &str,
+        seized_assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    /// Deletes everything written at or after `start_block`, for reorg handling.
+    async fn revert_from(&self, start_block: i64) -> eyre::Result<()>;
+
+    /// Oracles backing at least one position with outstanding borrow shares and
+    /// collateral, i.e. the ones worth fetching a price for.
+    async fn oracles_with_open_positions(&self) -> eyre::Result<Vec<Address>>;
+
+    async fn upsert_oracle_price(
+        &self,
+        oracle: Address,
+        price: u128,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()>;
+
+    /// Open positions (borrow_shares > 0 and collateral > 0) with a price recorded
+    /// at `block_number`, joined with their market's terms.
+    async fn open_positions_at(&self, block_number: i64) -> eyre::Result<Vec<PositionHealth>>;
+
+    /// The last block `backfill::run` fully committed, if any. Lets a backfill
+    /// that's restarted (after a clean exit or a crash partway through) resume
+    /// from where it left off instead of re-walking, and double-counting, a
+    /// range it already processed.
+    async fn backfill_checkpoint(&self) -> eyre::Result<Option<i64>>;
+
+    /// Records `block_number` as the last backfilled block. Callers write this
+    /// inside the same `begin`/`commit` transaction as that block's event
+    /// writes, so the checkpoint and the data it describes always advance
+    /// together.
+    async fn set_backfill_checkpoint(&self, block_number: i64) -> eyre::Result<()>;
+
+    /// Starts a transaction. Every write made through this trait between `begin`
+    /// and `commit`/`rollback` applies atomically, so a notification that fails
+    /// partway through (a bad event, a lost connection) leaves no half-updated
+    /// rows behind for the ExEx to re-process from an inconsistent state.
+    async fn begin(&self) -> eyre::Result<()>;
+
+    async fn commit(&self) -> eyre::Result<()>;
+
+    async fn rollback(&self) -> eyre::Result<()>;
+}
@@ -0,0 +1,696 @@
+use std::sync::Arc;
+
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use reth_tracing::tracing::error;
+use rustls::ClientConfig;
+use tokio::sync::Mutex;
+use tokio_postgres::Client;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use crate::accrual;
+use crate::migrations::{self, Migration};
+
+use super::{MorphoStore, NewMarket, PositionHealth};
+
+/// Lets operators point the ExEx at a shared Postgres database instead of a local
+/// SQLite file, so multiple Morpho markets can be queried from one place with
+/// regular SQL dashboards.
+///
+/// The client is behind a `tokio::sync::Mutex`, matching `SqliteStore`: `begin`/
+/// `commit`/`rollback` are raw `BEGIN`/`COMMIT`/`ROLLBACK` over the connection.
+/// Each trait method holds the lock for its whole duration, so one method's
+/// statements never interleave with another's. The lock is not held from `begin`
+/// to `commit`, so the open transaction takes in every write made through this
+/// store in between, whichever caller makes it.
+pub struct PostgresStore {
+    client: Mutex<Client>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        up_sql: r#"
+        CREATE TABLE markets (
+            id TEXT PRIMARY KEY,
+            loan_token TEXT NOT NULL,
+            collateral_token TEXT NOT NULL,
+            oracle TEXT NOT NULL,
+            irm TEXT NOT NULL,
+            lltv TEXT NOT NULL,
+            total_borrow_assets TEXT NOT NULL,
+            total_borrow_shares TEXT NOT NULL,
+            last_update BIGINT NOT NULL
+        );
+
+        CREATE TABLE positions (
+            market_id TEXT NOT NULL,
+            borrower TEXT NOT NULL,
+            borrow_shares TEXT NOT NULL,
+            collateral TEXT NOT NULL,
+            last_updated BIGINT NOT NULL,
+            PRIMARY KEY (market_id, borrower)
+        );
+
+        CREATE TABLE oracle_prices (
+            oracle_address TEXT NOT NULL,
+            price TEXT NOT NULL,
+            block_number BIGINT NOT NULL,
+            timestamp BIGINT NOT NULL,
+            PRIMARY KEY (oracle_address, block_number)
+        );
+
+        CREATE TABLE market_states (
+            market_id TEXT NOT NULL,
+            total_borrow_assets TEXT NOT NULL,
+            total_borrow_shares TEXT NOT NULL,
+            log_index BIGINT NOT NULL,
+            block_number BIGINT NOT NULL,
+            timestamp BIGINT NOT NULL,
+            PRIMARY KEY (market_id, block_number, log_index)
+        );
+    "#,
+    },
+    Migration {
+        version: 2,
+        up_sql: r#"
+        -- Intern every address seen so far into a BIGINT id, so markets/positions/
+        -- oracle_prices stop repeating 42-char hex strings.
+        CREATE TABLE addresses (
+            address_id BIGSERIAL PRIMARY KEY,
+            address TEXT NOT NULL UNIQUE
+        );
+
+        INSERT INTO addresses (address)
+        SELECT DISTINCT address FROM (
+            SELECT loan_token AS address FROM markets
+            UNION SELECT collateral_token FROM markets
+            UNION SELECT oracle FROM markets
+            UNION SELECT irm FROM markets
+            UNION SELECT borrower FROM positions
+            UNION SELECT oracle_address FROM oracle_prices
+        ) AS seen;
+
+        CREATE TABLE markets_new (
+            id TEXT PRIMARY KEY,
+            loan_token_id BIGINT NOT NULL REFERENCES addresses(address_id),
+            collateral_token_id BIGINT NOT NULL REFERENCES addresses(address_id),
+            oracle_id BIGINT NOT NULL REFERENCES addresses(address_id),
+            irm_id BIGINT NOT NULL REFERENCES addresses(address_id),
+            lltv TEXT NOT NULL,
+            total_borrow_assets TEXT NOT NULL,
+            total_borrow_shares TEXT NOT NULL,
+            last_update BIGINT NOT NULL
+        );
+        INSERT INTO markets_new
+        SELECT m.id, lt.address_id, ct.address_id, o.address_id, i.address_id,
+               m.lltv, m.total_borrow_assets, m.total_borrow_shares, m.last_update
+        FROM markets m
+        JOIN addresses lt ON lt.address = m.loan_token
+        JOIN addresses ct ON ct.address = m.collateral_token
+        JOIN addresses o ON o.address = m.oracle
+        JOIN addresses i ON i.address = m.irm;
+        DROP TABLE markets;
+        ALTER TABLE markets_new RENAME TO markets;
+
+        CREATE TABLE positions_new (
+            market_id TEXT NOT NULL,
+            borrower_id BIGINT NOT NULL REFERENCES addresses(address_id),
+            borrow_shares TEXT NOT NULL,
+            collateral TEXT NOT NULL,
+            last_updated BIGINT NOT NULL,
+            PRIMARY KEY (market_id, borrower_id)
+        );
+        INSERT INTO positions_new
+        SELECT p.market_id, a.address_id, p.borrow_shares, p.collateral, p.last_updated
+        FROM positions p JOIN addresses a ON a.address = p.borrower;
+        DROP TABLE positions;
+        ALTER TABLE positions_new RENAME TO positions;
+
+        CREATE TABLE oracle_prices_new (
+            oracle_id BIGINT NOT NULL REFERENCES addresses(address_id),
+            price TEXT NOT NULL,
+            block_number BIGINT NOT NULL,
+            timestamp BIGINT NOT NULL,
+            PRIMARY KEY (oracle_id, block_number)
+        );
+        INSERT INTO oracle_prices_new
+        SELECT a.address_id, op.price, op.block_number, op.timestamp
+        FROM oracle_prices op JOIN addresses a ON a.address = op.oracle_address;
+        DROP TABLE oracle_prices;
+        ALTER TABLE oracle_prices_new RENAME TO oracle_prices;
+
+        CREATE INDEX idx_market_states_market_block ON market_states(market_id, block_number);
+        CREATE INDEX idx_positions_market ON positions(market_id);
+    "#,
+    },
+    Migration {
+        version: 3,
+        up_sql: r#"
+        -- Single-row table tracking how far `backfill::run` has gotten, so a
+        -- restarted backfill resumes instead of re-walking an already-processed range.
+        CREATE TABLE backfill_checkpoint (
+            id BIGINT PRIMARY KEY CHECK (id = 0),
+            block_number BIGINT NOT NULL
+        );
+    "#,
+    },
+];
+
+impl PostgresStore {
+    pub async fn connect(connection_string: &str) -> eyre::Result<Self> {
+        let tls_config = ClientConfig::builder()
+            .with_root_certificates(root_certs()?)
+            .with_no_client_auth();
+        let connector = MakeRustlsConnect::new(tls_config);
+
+        let (mut client, connection) =
+            tokio_postgres::connect(connection_string, connector).await?;
+
+        tokio::spawn(async move {
+            if let Err(error) = connection.await {
+                error!(%error, "postgres connection closed");
+            }
+        });
+
+        migrations::apply_postgres(&mut client, MIGRATIONS).await?;
+
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+}
+
+fn root_certs() -> eyre::Result<Arc<rustls::RootCertStore>> {
+    let mut store = rustls::RootCertStore::empty();
+    store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    Ok(Arc::new(store))
+}
+
+/// Applies a signed delta to a market's running `total_borrow_assets`/
+/// `total_borrow_shares`, persists the new totals back into `markets`, and
+/// returns them so the caller can snapshot them into `market_states`. u128, not
+/// f64, so a market with more than ~15 significant digits of borrow assets
+/// doesn't silently lose precision.
+async fn accrue_market_totals(
+    client: &Client,
+    market_id: &str,
+    delta_assets: i128,
+    delta_shares: i128,
+    timestamp: i64,
+) -> eyre::Result<(String, String)> {
+    let row = client
+        .query_one(
+            "SELECT total_borrow_assets, total_borrow_shares FROM markets WHERE id = $1",
+            &[&market_id],
+        )
+        .await?;
+    let total_assets: String = row.get(0);
+    let total_shares: String = row.get(1);
+
+    let total_assets = accrual::apply_delta(total_assets.parse::<u128>()?, delta_assets)?;
+    let total_shares = accrual::apply_delta(total_shares.parse::<u128>()?, delta_shares)?;
+    let total_assets = total_assets.to_string();
+    let total_shares = total_shares.to_string();
+
+    client
+        .execute(
+            "UPDATE markets SET total_borrow_assets = $1, total_borrow_shares = $2, last_update = $3 WHERE id = $4",
+            &[&total_assets, &total_shares, &timestamp, &market_id],
+        )
+        .await?;
+
+    Ok((total_assets, total_shares))
+}
+
+/// Looks up `address`'s interned id, inserting it if this is the first time it's
+/// been seen.
+async fn intern_address(client: &Client, address: &str) -> eyre::Result<i64> {
+    let row = client
+        .query_one(
+            r#"
+            INSERT INTO addresses (address) VALUES ($1)
+            ON CONFLICT (address) DO UPDATE SET address = excluded.address
+            RETURNING address_id
+            "#,
+            &[&address],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+#[async_trait]
+impl MorphoStore for PostgresStore {
+    async fn create_market(&self, market: NewMarket) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+
+        let loan_token_id = intern_address(&client, &market.loan_token).await?;
+        let collateral_token_id = intern_address(&client, &market.collateral_token).await?;
+        let oracle_id = intern_address(&client, &market.oracle).await?;
+        let irm_id = intern_address(&client, &market.irm).await?;
+
+        client
+            .execute(
+                "INSERT INTO markets VALUES ($1, $2, $3, $4, $5, $6, '0', '0', $7)",
+                &[
+                    &market.id,
+                    &loan_token_id,
+                    &collateral_token_id,
+                    &oracle_id,
+                    &irm_id,
+                    &market.lltv,
+                    &market.timestamp,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn supply_collateral(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        assets: &str,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+        let borrower_id = intern_address(&client, borrower).await?;
+
+        client
+            .execute(
+                r#"
+                INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+                VALUES ($1, $2, '0', $3, $4)
+                ON CONFLICT (market_id, borrower_id) DO UPDATE SET
+                collateral = (CAST(positions.collateral AS NUMERIC) + CAST($3 AS NUMERIC))::TEXT,
+                last_updated = $4
+                "#,
+                &[&market_id, &borrower_id, &assets, &timestamp],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn borrow(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        shares: &str,
+        assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+        let borrower_id = intern_address(&client, borrower).await?;
+
+        client
+            .execute(
+                r#"
+                INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+                VALUES ($1, $2, $3, '0', $4)
+                ON CONFLICT (market_id, borrower_id) DO UPDATE SET
+                borrow_shares = (CAST(positions.borrow_shares AS NUMERIC) + CAST($3 AS NUMERIC))::TEXT,
+                last_updated = $4
+                "#,
+                &[&market_id, &borrower_id, &shares, &timestamp],
+            )
+            .await?;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &client,
+            market_id,
+            accrual::parse_amount(assets)?,
+            accrual::parse_amount(shares)?,
+            timestamp,
+        )
+        .await?;
+
+        client
+            .execute(
+                "INSERT INTO market_states VALUES ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &market_id,
+                    &total_borrow_assets,
+                    &total_borrow_shares,
+                    &log_index,
+                    &block_number,
+                    &timestamp,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn repay(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        shares: &str,
+        assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+        let borrower_id = intern_address(&client, borrower).await?;
+
+        client
+            .execute(
+                r#"
+                INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+                VALUES ($1, $2, '0', '0', $3)
+                ON CONFLICT (market_id, borrower_id) DO UPDATE SET
+                borrow_shares = (CAST(positions.borrow_shares AS NUMERIC) - CAST($4 AS NUMERIC))::TEXT,
+                last_updated = $3
+                "#,
+                &[&market_id, &borrower_id, &timestamp, &shares],
+            )
+            .await?;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &client,
+            market_id,
+            -accrual::parse_amount(assets)?,
+            -accrual::parse_amount(shares)?,
+            timestamp,
+        )
+        .await?;
+
+        client
+            .execute(
+                "INSERT INTO market_states VALUES ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &market_id,
+                    &total_borrow_assets,
+                    &total_borrow_shares,
+                    &log_index,
+                    &block_number,
+                    &timestamp,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn withdraw_collateral(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        assets: &str,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+        let borrower_id = intern_address(&client, borrower).await?;
+
+        client
+            .execute(
+                r#"
+                INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+                VALUES ($1, $2, '0', '0', $3)
+                ON CONFLICT (market_id, borrower_id) DO UPDATE SET
+                collateral = (CAST(positions.collateral AS NUMERIC) - CAST($4 AS NUMERIC))::TEXT,
+                last_updated = $3
+                "#,
+                &[&market_id, &borrower_id, &timestamp, &assets],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn accrue_interest(
+        &self,
+        market_id: &str,
+        interest: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &client,
+            market_id,
+            accrual::parse_amount(interest)?,
+            0,
+            timestamp,
+        )
+        .await?;
+
+        client
+            .execute(
+                "INSERT INTO market_states VALUES ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &market_id,
+                    &total_borrow_assets,
+                    &total_borrow_shares,
+                    &log_index,
+                    &block_number,
+                    &timestamp,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn liquidate(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        repaid_shares: &str,
+        repaid_assets: &str,
+        bad_debt_shares: &str,
+        bad_debt_assets: &str,
+        seized_assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+        let borrower_id = intern_address(&client, borrower).await?;
+
+        client
+            .execute(
+                r#"
+                INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+                VALUES ($1, $2, '0', '0', $3)
+                ON CONFLICT (market_id, borrower_id) DO UPDATE SET
+                borrow_shares = (CAST(positions.borrow_shares AS NUMERIC) - CAST($4 AS NUMERIC) - CAST($5 AS NUMERIC))::TEXT,
+                collateral = (CAST(positions.collateral AS NUMERIC) - CAST($6 AS NUMERIC))::TEXT,
+                last_updated = $3
+                "#,
+                &[
+                    &market_id,
+                    &borrower_id,
+                    &timestamp,
+                    &repaid_shares,
+                    &bad_debt_shares,
+                    &seized_assets,
+                ],
+            )
+            .await?;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &client,
+            market_id,
+            -(accrual::parse_amount(repaid_assets)? + accrual::parse_amount(bad_debt_assets)?),
+            -(accrual::parse_amount(repaid_shares)? + accrual::parse_amount(bad_debt_shares)?),
+            timestamp,
+        )
+        .await?;
+
+        client
+            .execute(
+                "INSERT INTO market_states VALUES ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &market_id,
+                    &total_borrow_assets,
+                    &total_borrow_shares,
+                    &log_index,
+                    &block_number,
+                    &timestamp,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revert_from(&self, start_block: i64) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+
+        client
+            .execute(
+                "DELETE FROM positions WHERE last_updated >= $1",
+                &[&start_block],
+            )
+            .await?;
+        client
+            .execute(
+                "DELETE FROM market_states WHERE block_number >= $1",
+                &[&start_block],
+            )
+            .await?;
+        client
+            .execute(
+                "DELETE FROM oracle_prices WHERE block_number >= $1",
+                &[&start_block],
+            )
+            .await?;
+
+        // market_states snapshots each market's running totals as of that event, so
+        // once the reverted rows are gone, rewind markets.total_borrow_assets/
+        // total_borrow_shares to the last surviving snapshot (or back to "0" if the
+        // market never had an event before start_block). Otherwise accrue_market_totals
+        // would keep compounding on top of totals the reorg already undid.
+        client
+            .batch_execute(
+                r#"
+                UPDATE markets SET
+                total_borrow_assets = COALESCE((
+                    SELECT total_borrow_assets FROM market_states
+                    WHERE market_states.market_id = markets.id
+                    ORDER BY block_number DESC, log_index DESC LIMIT 1
+                ), '0'),
+                total_borrow_shares = COALESCE((
+                    SELECT total_borrow_shares FROM market_states
+                    WHERE market_states.market_id = markets.id
+                    ORDER BY block_number DESC, log_index DESC LIMIT 1
+                ), '0')
+                "#,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn oracles_with_open_positions(&self) -> eyre::Result<Vec<Address>> {
+        let client = self.client.lock().await;
+        let rows = client
+            .query(
+                r#"
+                SELECT DISTINCT a.address
+                FROM markets m
+                INNER JOIN positions p ON p.market_id = m.id
+                INNER JOIN addresses a ON a.address_id = m.oracle_id
+                WHERE CAST(p.borrow_shares AS NUMERIC) > 0 AND CAST(p.collateral AS NUMERIC) > 0
+                "#,
+                &[],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| row.get::<_, String>(0).parse().ok())
+            .collect())
+    }
+
+    async fn upsert_oracle_price(
+        &self,
+        oracle: Address,
+        price: u128,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let client = self.client.lock().await;
+        let oracle_id = intern_address(&client, &oracle.to_string()).await?;
+
+        client
+            .execute(
+                r#"
+                INSERT INTO oracle_prices (oracle_id, price, block_number, timestamp)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (oracle_id, block_number) DO UPDATE SET
+                price = excluded.price,
+                timestamp = excluded.timestamp
+                "#,
+                &[&oracle_id, &price.to_string(), &block_number, &timestamp],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn open_positions_at(&self, block_number: i64) -> eyre::Result<Vec<PositionHealth>> {
+        let client = self.client.lock().await;
+        let rows = client
+            .query(
+                r#"
+                SELECT
+                    p.market_id,
+                    borrower.address,
+                    p.borrow_shares,
+                    p.collateral,
+                    m.total_borrow_assets,
+                    m.total_borrow_shares,
+                    m.lltv,
+                    op.price
+                FROM positions p
+                INNER JOIN markets m ON p.market_id = m.id
+                INNER JOIN addresses borrower ON borrower.address_id = p.borrower_id
+                INNER JOIN oracle_prices op ON m.oracle_id = op.oracle_id
+                WHERE CAST(p.borrow_shares AS NUMERIC) > 0
+                AND CAST(p.collateral AS NUMERIC) > 0
+                AND op.block_number = $1
+                "#,
+                &[&block_number],
+            )
+            .await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| PositionHealth {
+                market_id: row.get(0),
+                borrower: row.get(1),
+                borrow_shares: row.get(2),
+                collateral: row.get(3),
+                total_borrow_assets: row.get(4),
+                total_borrow_shares: row.get(5),
+                lltv: row.get(6),
+                price: row.get(7),
+            })
+            .collect())
+    }
+
+    async fn backfill_checkpoint(&self) -> eyre::Result<Option<i64>> {
+        let client = self.client.lock().await;
+        let row = client
+            .query_opt(
+                "SELECT block_number FROM backfill_checkpoint WHERE id = 0",
+                &[],
+            )
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn set_backfill_checkpoint(&self, block_number: i64) -> eyre::Result<()> {
+        self.client
+            .lock()
+            .await
+            .execute(
+                r#"
+                INSERT INTO backfill_checkpoint (id, block_number) VALUES (0, $1)
+                ON CONFLICT (id) DO UPDATE SET block_number = excluded.block_number
+                "#,
+                &[&block_number],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn begin(&self) -> eyre::Result<()> {
+        self.client.lock().await.batch_execute("BEGIN").await?;
+        Ok(())
+    }
+
+    async fn commit(&self) -> eyre::Result<()> {
+        self.client.lock().await.batch_execute("COMMIT").await?;
+        Ok(())
+    }
+
+    async fn rollback(&self) -> eyre::Result<()> {
+        self.client.lock().await.batch_execute("ROLLBACK").await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,656 @@
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension};
+use tokio::sync::Mutex;
+
+use crate::accrual;
+use crate::migrations::{self, Migration};
+
+use super::{MorphoStore, NewMarket, PositionHealth};
+
+/// The original backend: a single local SQLite file.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        up_sql: r#"
+        CREATE TABLE markets (
+            id TEXT PRIMARY KEY,
+            loan_token TEXT NOT NULL,
+            collateral_token TEXT NOT NULL,
+            oracle TEXT NOT NULL,
+            irm TEXT NOT NULL,
+            lltv TEXT NOT NULL,
+            total_borrow_assets TEXT NOT NULL,
+            total_borrow_shares TEXT NOT NULL,
+            last_update INTEGER NOT NULL
+        );
+
+        CREATE TABLE positions (
+            market_id TEXT NOT NULL,
+            borrower TEXT NOT NULL,
+            borrow_shares TEXT NOT NULL,
+            collateral TEXT NOT NULL,
+            last_updated INTEGER NOT NULL,
+            PRIMARY KEY (market_id, borrower)
+        );
+
+        CREATE TABLE oracle_prices (
+            oracle_address TEXT NOT NULL,
+            price TEXT NOT NULL,
+            block_number INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL,
+            PRIMARY KEY (oracle_address, block_number)
+        );
+
+        CREATE TABLE market_states (
+            market_id TEXT NOT NULL,
+            total_borrow_assets TEXT NOT NULL,
+            total_borrow_shares TEXT NOT NULL,
+            log_index INTEGER NOT NULL,
+            block_number INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL,
+            PRIMARY KEY (market_id, block_number, log_index)
+        );
+    "#,
+    },
+    Migration {
+        version: 2,
+        up_sql: r#"
+        -- Intern every address seen so far into an integer id, so markets/
+        -- positions/oracle_prices stop repeating 42-char hex strings.
+        CREATE TABLE addresses (
+            address_id INTEGER PRIMARY KEY,
+            address TEXT NOT NULL UNIQUE
+        );
+
+        INSERT INTO addresses (address)
+        SELECT DISTINCT address FROM (
+            SELECT loan_token AS address FROM markets
+            UNION SELECT collateral_token FROM markets
+            UNION SELECT oracle FROM markets
+            UNION SELECT irm FROM markets
+            UNION SELECT borrower FROM positions
+            UNION SELECT oracle_address FROM oracle_prices
+        );
+
+        CREATE TABLE markets_new (
+            id TEXT PRIMARY KEY,
+            loan_token_id INTEGER NOT NULL REFERENCES addresses(address_id),
+            collateral_token_id INTEGER NOT NULL REFERENCES addresses(address_id),
+            oracle_id INTEGER NOT NULL REFERENCES addresses(address_id),
+            irm_id INTEGER NOT NULL REFERENCES addresses(address_id),
+            lltv TEXT NOT NULL,
+            total_borrow_assets TEXT NOT NULL,
+            total_borrow_shares TEXT NOT NULL,
+            last_update INTEGER NOT NULL
+        );
+        INSERT INTO markets_new
+        SELECT m.id, lt.address_id, ct.address_id, o.address_id, i.address_id,
+               m.lltv, m.total_borrow_assets, m.total_borrow_shares, m.last_update
+        FROM markets m
+        JOIN addresses lt ON lt.address = m.loan_token
+        JOIN addresses ct ON ct.address = m.collateral_token
+        JOIN addresses o ON o.address = m.oracle
+        JOIN addresses i ON i.address = m.irm;
+        DROP TABLE markets;
+        ALTER TABLE markets_new RENAME TO markets;
+
+        CREATE TABLE positions_new (
+            market_id TEXT NOT NULL,
+            borrower_id INTEGER NOT NULL REFERENCES addresses(address_id),
+            borrow_shares TEXT NOT NULL,
+            collateral TEXT NOT NULL,
+            last_updated INTEGER NOT NULL,
+            PRIMARY KEY (market_id, borrower_id)
+        );
+        INSERT INTO positions_new
+        SELECT p.market_id, a.address_id, p.borrow_shares, p.collateral, p.last_updated
+        FROM positions p JOIN addresses a ON a.address = p.borrower;
+        DROP TABLE positions;
+        ALTER TABLE positions_new RENAME TO positions;
+
+        CREATE TABLE oracle_prices_new (
+            oracle_id INTEGER NOT NULL REFERENCES addresses(address_id),
+            price TEXT NOT NULL,
+            block_number INTEGER NOT NULL,
+            timestamp INTEGER NOT NULL,
+            PRIMARY KEY (oracle_id, block_number)
+        );
+        INSERT INTO oracle_prices_new
+        SELECT a.address_id, op.price, op.block_number, op.timestamp
+        FROM oracle_prices op JOIN addresses a ON a.address = op.oracle_address;
+        DROP TABLE oracle_prices;
+        ALTER TABLE oracle_prices_new RENAME TO oracle_prices;
+
+        CREATE INDEX idx_market_states_market_block ON market_states(market_id, block_number);
+        CREATE INDEX idx_positions_market ON positions(market_id);
+    "#,
+    },
+    Migration {
+        version: 3,
+        up_sql: r#"
+        -- Single-row table tracking how far `backfill::run` has gotten, so a
+        -- restarted backfill resumes instead of re-walking an already-processed range.
+        CREATE TABLE backfill_checkpoint (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            block_number INTEGER NOT NULL
+        );
+    "#,
+    },
+];
+
+impl SqliteStore {
+    pub fn open(path: &str) -> eyre::Result<Self> {
+        let mut connection = Connection::open(path)?;
+        migrations::apply_sqlite(&mut connection, MIGRATIONS)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+/// Looks up `address`'s interned id, inserting it if this is the first time it's
+/// been seen.
+fn intern_address(connection: &Connection, address: &str) -> rusqlite::Result<i64> {
+    connection.query_row(
+        r#"
+        INSERT INTO addresses (address) VALUES (?)
+        ON CONFLICT(address) DO UPDATE SET address = excluded.address
+        RETURNING address_id
+        "#,
+        [address],
+        |row| row.get(0),
+    )
+}
+
+/// Applies a signed delta to a market's running `total_borrow_assets`/
+/// `total_borrow_shares`, persists the new totals back into `markets`, and
+/// returns them so the caller can snapshot them into `market_states`. u128, not
+/// f64, so a market with more than ~15 significant digits of borrow assets
+/// doesn't silently lose precision.
+fn accrue_market_totals(
+    connection: &Connection,
+    market_id: &str,
+    delta_assets: i128,
+    delta_shares: i128,
+    timestamp: i64,
+) -> eyre::Result<(String, String)> {
+    let (total_assets, total_shares): (String, String) = connection.query_row(
+        "SELECT total_borrow_assets, total_borrow_shares FROM markets WHERE id = ?",
+        [market_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let total_assets = accrual::apply_delta(total_assets.parse::<u128>()?, delta_assets)?;
+    let total_shares = accrual::apply_delta(total_shares.parse::<u128>()?, delta_shares)?;
+    let total_assets = total_assets.to_string();
+    let total_shares = total_shares.to_string();
+
+    connection.execute(
+        "UPDATE markets SET total_borrow_assets = ?, total_borrow_shares = ?, last_update = ? WHERE id = ?",
+        (&total_assets, &total_shares, timestamp, market_id),
+    )?;
+
+    Ok((total_assets, total_shares))
+}
+
+#[async_trait]
+impl MorphoStore for SqliteStore {
+    async fn create_market(&self, market: NewMarket) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+
+        let loan_token_id = intern_address(&connection, &market.loan_token)?;
+        let collateral_token_id = intern_address(&connection, &market.collateral_token)?;
+        let oracle_id = intern_address(&connection, &market.oracle)?;
+        let irm_id = intern_address(&connection, &market.irm)?;
+
+        connection.execute(
+            "INSERT INTO markets VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            (
+                market.id,
+                loan_token_id,
+                collateral_token_id,
+                oracle_id,
+                irm_id,
+                market.lltv,
+                "0",
+                "0",
+                market.timestamp,
+            ),
+        )?;
+        Ok(())
+    }
+
+    async fn supply_collateral(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        assets: &str,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+        let borrower_id = intern_address(&connection, borrower)?;
+
+        connection.execute(
+            r#"
+            INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(market_id, borrower_id) DO UPDATE SET
+            collateral = CAST(collateral AS INTEGER) + ?,
+            last_updated = ?
+            "#,
+            (
+                market_id,
+                borrower_id,
+                "0",
+                assets,
+                timestamp,
+                assets,
+                timestamp,
+            ),
+        )?;
+        Ok(())
+    }
+
+    async fn borrow(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        shares: &str,
+        assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+        let borrower_id = intern_address(&connection, borrower)?;
+
+        connection.execute(
+            r#"
+            INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(market_id, borrower_id) DO UPDATE SET
+            borrow_shares = CAST(borrow_shares AS INTEGER) + ?,
+            last_updated = ?
+            "#,
+            (
+                market_id,
+                borrower_id,
+                shares,
+                "0",
+                timestamp,
+                shares,
+                timestamp,
+            ),
+        )?;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &connection,
+            market_id,
+            accrual::parse_amount(assets)?,
+            accrual::parse_amount(shares)?,
+            timestamp,
+        )?;
+
+        connection.execute(
+            "INSERT INTO market_states VALUES (?, ?, ?, ?, ?, ?)",
+            (
+                market_id,
+                total_borrow_assets,
+                total_borrow_shares,
+                log_index,
+                block_number,
+                timestamp,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    async fn repay(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        shares: &str,
+        assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+        let borrower_id = intern_address(&connection, borrower)?;
+
+        connection.execute(
+            r#"
+            INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(market_id, borrower_id) DO UPDATE SET
+            borrow_shares = CAST(borrow_shares AS INTEGER) - ?,
+            last_updated = ?
+            "#,
+            (
+                market_id,
+                borrower_id,
+                "0",
+                "0",
+                timestamp,
+                shares,
+                timestamp,
+            ),
+        )?;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &connection,
+            market_id,
+            -accrual::parse_amount(assets)?,
+            -accrual::parse_amount(shares)?,
+            timestamp,
+        )?;
+
+        connection.execute(
+            "INSERT INTO market_states VALUES (?, ?, ?, ?, ?, ?)",
+            (
+                market_id,
+                total_borrow_assets,
+                total_borrow_shares,
+                log_index,
+                block_number,
+                timestamp,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    async fn withdraw_collateral(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        assets: &str,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+        let borrower_id = intern_address(&connection, borrower)?;
+
+        connection.execute(
+            r#"
+            INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(market_id, borrower_id) DO UPDATE SET
+            collateral = CAST(collateral AS INTEGER) - ?,
+            last_updated = ?
+            "#,
+            (
+                market_id,
+                borrower_id,
+                "0",
+                "0",
+                timestamp,
+                assets,
+                timestamp,
+            ),
+        )?;
+        Ok(())
+    }
+
+    async fn accrue_interest(
+        &self,
+        market_id: &str,
+        interest: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &connection,
+            market_id,
+            accrual::parse_amount(interest)?,
+            0,
+            timestamp,
+        )?;
+
+        connection.execute(
+            "INSERT INTO market_states VALUES (?, ?, ?, ?, ?, ?)",
+            (
+                market_id,
+                total_borrow_assets,
+                total_borrow_shares,
+                log_index,
+                block_number,
+                timestamp,
+            ),
+        )?;
+        Ok(())
+    }
+
+    async fn liquidate(
+        &self,
+        market_id: &str,
+        borrower: &str,
+        repaid_shares: &str,
+        repaid_assets: &str,
+        bad_debt_shares: &str,
+        bad_debt_assets: &str,
+        seized_assets: &str,
+        log_index: i64,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+        let borrower_id = intern_address(&connection, borrower)?;
+
+        connection.execute(
+            r#"
+            INSERT INTO positions (market_id, borrower_id, borrow_shares, collateral, last_updated)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(market_id, borrower_id) DO UPDATE SET
+            borrow_shares = CAST(borrow_shares AS INTEGER) - ? - ?,
+            collateral = CAST(collateral AS INTEGER) - ?,
+            last_updated = ?
+            "#,
+            (
+                market_id,
+                borrower_id,
+                "0",
+                "0",
+                timestamp,
+                repaid_shares,
+                bad_debt_shares,
+                seized_assets,
+                timestamp,
+            ),
+        )?;
+
+        let (total_borrow_assets, total_borrow_shares) = accrue_market_totals(
+            &connection,
+            market_id,
+            -(accrual::parse_amount(repaid_assets)? + accrual::parse_amount(bad_debt_assets)?),
+            -(accrual::parse_amount(repaid_shares)? + accrual::parse_amount(bad_debt_shares)?),
+            timestamp,
+        )?;
+
+        connection.execute(
+            "INSERT INTO market_states VALUES (?, ?, ?, ?, ?, ?)",
+            (
+                market_id,
+                total_borrow_assets,
+                total_borrow_shares,
+                log_index,
+                block_number,
+                timestamp,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    async fn revert_from(&self, start_block: i64) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+
+        connection.execute(
+            "DELETE FROM positions WHERE last_updated >= ?",
+            [start_block],
+        )?;
+        connection.execute(
+            "DELETE FROM market_states WHERE block_number >= ?",
+            [start_block],
+        )?;
+        connection.execute(
+            "DELETE FROM oracle_prices WHERE block_number >= ?",
+            [start_block],
+        )?;
+
+        // market_states snapshots each market's running totals as of that event, so
+        // once the reverted rows are gone, rewind markets.total_borrow_assets/
+        // total_borrow_shares to the last surviving snapshot (or back to "0" if the
+        // market never had an event before start_block). Otherwise accrue_market_totals
+        // would keep compounding on top of totals the reorg already undid.
+        connection.execute_batch(
+            r#"
+            UPDATE markets SET
+            total_borrow_assets = COALESCE((
+                SELECT total_borrow_assets FROM market_states
+                WHERE market_states.market_id = markets.id
+                ORDER BY block_number DESC, log_index DESC LIMIT 1
+            ), '0'),
+            total_borrow_shares = COALESCE((
+                SELECT total_borrow_shares FROM market_states
+                WHERE market_states.market_id = markets.id
+                ORDER BY block_number DESC, log_index DESC LIMIT 1
+            ), '0')
+            "#,
+        )?;
+
+        Ok(())
+    }
+
+    async fn oracles_with_open_positions(&self) -> eyre::Result<Vec<Address>> {
+        let connection = self.connection.lock().await;
+        let mut stmt = connection.prepare(
+            r#"
+            SELECT DISTINCT a.address
+            FROM markets m
+            INNER JOIN positions p ON p.market_id = m.id
+            INNER JOIN addresses a ON a.address_id = m.oracle_id
+            WHERE p.borrow_shares > 0 AND p.collateral > 0
+            "#,
+        )?;
+
+        let oracles = stmt
+            .query_map((), |row| row.get::<_, String>(0))?
+            .filter_map(|result| result.ok())
+            .filter_map(|address| address.parse().ok())
+            .collect();
+
+        Ok(oracles)
+    }
+
+    async fn upsert_oracle_price(
+        &self,
+        oracle: Address,
+        price: u128,
+        block_number: i64,
+        timestamp: i64,
+    ) -> eyre::Result<()> {
+        let connection = self.connection.lock().await;
+        let oracle_id = intern_address(&connection, &oracle.to_string())?;
+
+        connection.execute(
+            r#"
+            INSERT INTO oracle_prices (oracle_id, price, block_number, timestamp)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT(oracle_id, block_number) DO UPDATE SET
+            price = excluded.price,
+            timestamp = excluded.timestamp
+            "#,
+            (oracle_id, price.to_string(), block_number, timestamp),
+        )?;
+        Ok(())
+    }
+
+    async fn open_positions_at(&self, block_number: i64) -> eyre::Result<Vec<PositionHealth>> {
+        let connection = self.connection.lock().await;
+        let mut stmt = connection.prepare(
+            r#"
+            SELECT
+                p.market_id,
+                borrower.address,
+                p.borrow_shares,
+                p.collateral,
+                m.total_borrow_assets,
+                m.total_borrow_shares,
+                m.lltv,
+                op.price
+            FROM positions p
+            INNER JOIN markets m ON p.market_id = m.id
+            INNER JOIN addresses borrower ON borrower.address_id = p.borrower_id
+            INNER JOIN oracle_prices op ON m.oracle_id = op.oracle_id
+            WHERE p.borrow_shares > 0
+            AND p.collateral > 0
+            AND op.block_number = ?
+            "#,
+        )?;
+
+        let rows = stmt
+            .query_map([block_number], |row| {
+                Ok(PositionHealth {
+                    market_id: row.get(0)?,
+                    borrower: row.get(1)?,
+                    borrow_shares: row.get(2)?,
+                    collateral: row.get(3)?,
+                    total_borrow_assets: row.get(4)?,
+                    total_borrow_shares: row.get(5)?,
+                    lltv: row.get(6)?,
+                    price: row.get(7)?,
+                })
+            })?
+            .filter_map(|result| result.ok())
+            .collect();
+
+        Ok(rows)
+    }
+
+    async fn backfill_checkpoint(&self) -> eyre::Result<Option<i64>> {
+        let connection = self.connection.lock().await;
+        let checkpoint = connection
+            .query_row(
+                "SELECT block_number FROM backfill_checkpoint WHERE id = 0",
+                (),
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(checkpoint)
+    }
+
+    async fn set_backfill_checkpoint(&self, block_number: i64) -> eyre::Result<()> {
+        self.connection.lock().await.execute(
+            r#"
+            INSERT INTO backfill_checkpoint (id, block_number) VALUES (0, ?)
+            ON CONFLICT(id) DO UPDATE SET block_number = excluded.block_number
+            "#,
+            [block_number],
+        )?;
+        Ok(())
+    }
+
+    async fn begin(&self) -> eyre::Result<()> {
+        self.connection.lock().await.execute_batch("BEGIN")?;
+        Ok(())
+    }
+
+    async fn commit(&self) -> eyre::Result<()> {
+        self.connection.lock().await.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    async fn rollback(&self) -> eyre::Result<()> {
+        self.connection.lock().await.execute_batch("ROLLBACK")?;
+        Ok(())
+    }
+}